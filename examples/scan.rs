@@ -6,8 +6,8 @@ use ak8963::{
 };
 
 pub fn main() {
-    let mut ak = Ak8963::new(
-        1, None, Sensitivity::Opt16bit, SampleRate::Opt100Hz).unwrap();
+    let mut ak = Ak8963::new_linux(
+        1, None, None, Sensitivity::Opt16bit, SampleRate::Opt100Hz).unwrap();
 
     loop {
         println!("Measurement: {:?}", ak.read_sample());
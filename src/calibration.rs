@@ -0,0 +1,141 @@
+//! Hard-iron / soft-iron calibration, so `Ak8963Sample::mag` readings can be
+//! corrected for nearby ferrous material rather than used raw.
+
+use ndarray::prelude::*;
+
+use super::Axis;
+
+/// Below this per-axis half-range (in the same units as `Ak8963Sample::mag`),
+/// `fit_diagonal` refuses to fit a scale for that axis rather than dividing
+/// by a near-zero range and producing `inf`/`NaN`.
+const MIN_HALF_RANGE: f32 = 1.0;
+
+/// Per-axis hard-iron offset and soft-iron scale, fit as a diagonal
+/// ellipsoid-to-sphere approximation. Fields are public so a `Calibration`
+/// can be persisted and reloaded by the caller.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Calibration {
+    /// Hard-iron offset per axis, in the same units as `Ak8963Sample::mag`.
+    pub bias: [f32; 3],
+    /// Soft-iron diagonal scale per axis.
+    pub scale: [f32; 3],
+}
+
+impl Default for Calibration {
+    /// The identity calibration: no offset, no scaling.
+    fn default() -> Self {
+        Calibration {
+            bias: [0.0; 3],
+            scale: [1.0; 3],
+        }
+    }
+}
+
+impl From<[f32; 6]> for Calibration {
+    /// Builds a `Calibration` from `[bias_x, bias_y, bias_z, scale_x,
+    /// scale_y, scale_z]`.
+    fn from(v: [f32; 6]) -> Self {
+        Calibration {
+            bias: [v[0], v[1], v[2]],
+            scale: [v[3], v[4], v[5]],
+        }
+    }
+}
+
+impl Calibration {
+    /// Applies `corrected = scale * (mag - bias)` per axis.
+    pub(crate) fn apply(&self, mag: &Array1<f32>) -> Array1<f32> {
+        array![
+            self.scale[0] * (mag[0] - self.bias[0]),
+            self.scale[1] * (mag[1] - self.bias[1]),
+            self.scale[2] * (mag[2] - self.bias[2]),
+        ]
+    }
+}
+
+/// Fits a diagonal hard-iron/soft-iron `Calibration` from the per-axis
+/// `min`/`max` observed while rotating the sensor through many orientations:
+/// hard-iron offset is `(max+min)/2`, soft-iron scale is the ratio of each
+/// axis's half-range to the average half-range.
+///
+/// Fails with the offending `Axis` if that axis's half-range is below
+/// `MIN_HALF_RANGE`, which would otherwise divide by (near) zero and yield
+/// `inf`/`NaN` scale, e.g. because the sensor was never rotated through that
+/// axis during the run.
+pub(crate) fn fit_diagonal(min: [f32; 3], max: [f32; 3]) -> Result<Calibration, Axis> {
+    let half_range = [
+        (max[0] - min[0]) / 2.0,
+        (max[1] - min[1]) / 2.0,
+        (max[2] - min[2]) / 2.0,
+    ];
+    if half_range[0] < MIN_HALF_RANGE { return Err(Axis::X); }
+    if half_range[1] < MIN_HALF_RANGE { return Err(Axis::Y); }
+    if half_range[2] < MIN_HALF_RANGE { return Err(Axis::Z); }
+
+    let avg_half_range = (half_range[0] + half_range[1] + half_range[2]) / 3.0;
+
+    Ok(Calibration {
+        bias: [
+            (max[0] + min[0]) / 2.0,
+            (max[1] + min[1]) / 2.0,
+            (max[2] + min[2]) / 2.0,
+        ],
+        scale: [
+            avg_half_range / half_range[0],
+            avg_half_range / half_range[1],
+            avg_half_range / half_range[2],
+        ],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_transforms_with_bias_and_scale() {
+        let calibration = Calibration {
+            bias: [1.0, -2.0, 0.5],
+            scale: [2.0, 1.0, 0.5],
+        };
+        let mag = array![3.0, 1.0, 4.5];
+        let corrected = calibration.apply(&mag);
+        assert_eq!(corrected[0], 4.0);   // 2.0 * (3.0 - 1.0)
+        assert_eq!(corrected[1], 3.0);   // 1.0 * (1.0 - -2.0)
+        assert_eq!(corrected[2], 2.0);   // 0.5 * (4.5 - 0.5)
+    }
+
+    #[test]
+    fn default_is_identity() {
+        let identity = Calibration::default();
+        let mag = array![3.0, 1.0, 4.5];
+        assert_eq!(identity.apply(&mag), mag);
+    }
+
+    #[test]
+    fn fit_diagonal_computes_bias_and_scale() {
+        let min = [-10.0, -20.0, -40.0];
+        let max = [10.0, 20.0, 40.0];
+        let calibration = fit_diagonal(min, max).unwrap();
+        assert_eq!(calibration.bias, [0.0, 0.0, 0.0]);
+        // half_range = [10, 20, 40], avg_half_range = 70/3
+        assert!((calibration.scale[0] - (70.0 / 3.0) / 10.0).abs() < 1e-6);
+        assert!((calibration.scale[1] - (70.0 / 3.0) / 20.0).abs() < 1e-6);
+        assert!((calibration.scale[2] - (70.0 / 3.0) / 40.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn fit_diagonal_reports_offset_bias() {
+        let min = [0.0, -20.0, -40.0];
+        let max = [20.0, 20.0, 40.0];
+        let calibration = fit_diagonal(min, max).unwrap();
+        assert_eq!(calibration.bias, [10.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn fit_diagonal_rejects_unrotated_axis() {
+        let min = [-10.0, -20.0, -0.1];
+        let max = [10.0, 20.0, 0.1];
+        assert_eq!(fit_diagonal(min, max), Err(Axis::Z));
+    }
+}
@@ -0,0 +1,141 @@
+//! Register layout and scaling for the AKM magnetometer family. The
+//! AK8963, AK09911 and AK09912 are pin- and protocol-compatible but differ
+//! in Fuse-ROM layout, data/status register offsets, and full-scale range.
+
+use ndarray::prelude::*;
+
+/// Which AKM magnetometer is attached. Defaults to `Ak8963` so existing
+/// calls keep working.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Chip {
+    Ak8963,
+    Ak09911,
+    Ak09912,
+}
+
+impl Default for Chip {
+    fn default() -> Self {
+        Chip::Ak8963
+    }
+}
+
+impl Chip {
+    /// Full-scale measurement range, in uT.
+    pub(crate) fn meas_range(&self) -> f32 {
+        match *self {
+            Chip::Ak8963 => 4912.0,
+            Chip::Ak09911 => 9830.0,
+            Chip::Ak09912 => 4912.0,
+        }
+    }
+
+    pub(crate) fn wia_addr(&self) -> u8 {
+        0x00
+    }
+
+    pub(crate) fn st1_addr(&self) -> u8 {
+        match *self {
+            Chip::Ak8963 => 0x02,
+            Chip::Ak09911 | Chip::Ak09912 => 0x10,
+        }
+    }
+
+    pub(crate) fn hxl_addr(&self) -> u8 {
+        match *self {
+            Chip::Ak8963 => 0x03,
+            Chip::Ak09911 | Chip::Ak09912 => 0x11,
+        }
+    }
+
+    pub(crate) fn asa_addr(&self) -> u8 {
+        match *self {
+            Chip::Ak8963 => 0x10,
+            Chip::Ak09911 | Chip::Ak09912 => 0x60,
+        }
+    }
+
+    /// Mode control register: power-down/continuous-measurement/fuse-ROM
+    /// select lives here. This is CNTL1 on the AK8963, but CNTL2 on the
+    /// AK09911/AK09912 (their CNTL1 is unrelated).
+    pub(crate) fn cntl1_addr(&self) -> u8 {
+        match *self {
+            Chip::Ak8963 => 0x0a,
+            Chip::Ak09911 | Chip::Ak09912 => 0x31,
+        }
+    }
+
+    /// Soft-reset register. This is CNTL2 on the AK8963, but CNTL3 on the
+    /// AK09911/AK09912.
+    pub(crate) fn cntl2_addr(&self) -> u8 {
+        match *self {
+            Chip::Ak8963 => 0x0b,
+            Chip::Ak09911 | Chip::Ak09912 => 0x32,
+        }
+    }
+
+    /// Self-test control register. Only implemented for the AK8963 so far
+    /// (see `Ak8963::self_test`).
+    pub(crate) fn astc_addr(&self) -> u8 {
+        match *self {
+            Chip::Ak8963 => 0x0c,
+            Chip::Ak09911 | Chip::Ak09912 => 0x0c,
+        }
+    }
+
+    /// Saturation (HOFL) bit position within the ST2 byte.
+    pub(crate) fn saturation_bit(&self) -> u8 {
+        1 << 3
+    }
+
+    /// Applies the chip's Fuse-ROM adjustment formula to the raw ASA bytes.
+    pub(crate) fn fuse_rom_adjustment(&self, asa: [u8; 3]) -> Array1<f32> {
+        match *self {
+            Chip::Ak8963 => array![
+                ((asa[0] as f32) - 128.0) / 256.0 + 1.0,
+                ((asa[1] as f32) - 128.0) / 256.0 + 1.0,
+                ((asa[2] as f32) - 128.0) / 256.0 + 1.0,
+            ],
+            Chip::Ak09911 | Chip::Ak09912 => array![
+                ((asa[0] as f32) - 128.0) / 128.0 + 1.0,
+                ((asa[1] as f32) - 128.0) / 128.0 + 1.0,
+                ((asa[2] as f32) - 128.0) / 128.0 + 1.0,
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuse_rom_adjustment_ak8963_uses_div_256_formula() {
+        let adjust = Chip::Ak8963.fuse_rom_adjustment([128, 0, 255]);
+        assert_eq!(adjust[0], 1.0);
+        assert_eq!(adjust[1], 0.5);
+        assert!((adjust[2] - 1.496_093_8).abs() < 1e-6);
+    }
+
+    #[test]
+    fn fuse_rom_adjustment_ak09911_uses_div_128_formula() {
+        let adjust = Chip::Ak09911.fuse_rom_adjustment([128, 0, 255]);
+        assert_eq!(adjust[0], 1.0);
+        assert_eq!(adjust[1], 0.0);
+        assert!((adjust[2] - 1.992_187_5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ak09911_and_ak09912_share_mode_and_data_registers() {
+        assert_eq!(Chip::Ak09911.cntl1_addr(), Chip::Ak09912.cntl1_addr());
+        assert_eq!(Chip::Ak09911.cntl2_addr(), Chip::Ak09912.cntl2_addr());
+        assert_eq!(Chip::Ak09911.st1_addr(), Chip::Ak09912.st1_addr());
+        assert_eq!(Chip::Ak09911.hxl_addr(), Chip::Ak09912.hxl_addr());
+        assert_eq!(Chip::Ak09911.asa_addr(), Chip::Ak09912.asa_addr());
+    }
+
+    #[test]
+    fn ak8963_mode_and_reset_registers_differ_from_ak0991x() {
+        assert_ne!(Chip::Ak8963.cntl1_addr(), Chip::Ak09911.cntl1_addr());
+        assert_ne!(Chip::Ak8963.cntl2_addr(), Chip::Ak09911.cntl2_addr());
+    }
+}
@@ -1,44 +1,43 @@
 //! A library for the AK8963 magnetometer.
+//!
+//! The driver is built on `embedded-hal` so it works with any `Write` +
+//! `WriteRead` i2c implementation. Enable the `linux` feature for a
+//! convenience constructor that wraps `i2cdev`/`std::thread::sleep`.
 
 extern crate byteorder;
 use byteorder::{
     ByteOrder,
     LittleEndian,
 };
-extern crate i2cdev;
-use i2cdev::core::*;
-use i2cdev::linux::{LinuxI2CDevice, LinuxI2CError};
+extern crate embedded_hal as hal;
+use hal::blocking::delay::DelayMs;
+use hal::blocking::i2c::{Write, WriteRead};
 #[macro_use]
 extern crate ndarray;
 use ndarray::prelude::*;
-use std::thread;
-use std::time;
 
-const MEAS_RANGE: f32 = 4912.0;  // UT = micro teslas
+mod calibration;
+pub use calibration::Calibration;
 
-fn get_i2c_bus_path(i2c_bus: i32) -> String {
-    format!("/dev/i2c-{}", i2c_bus)
-}
+mod chip;
+pub use chip::Chip;
 
-#[derive(Clone, Copy)]
-pub enum Ak8963Reg {
-    St1 = 0x02,
-    Hxl = 0x03,  // XoutL
-    Cntl1 = 0x0a,
-    Asax = 0x10,  // Sensitivity values
-}
+#[cfg(feature = "linux")]
+mod linux;
+#[cfg(feature = "linux")]
+pub use linux::LinuxError;
 
-impl Ak8963Reg {
-    fn addr(&self) -> u8 {
-        *self as u8
-    }
-}
+/// The AKM magnetometer family always reports this value from the WIA
+/// register.
+const DEVICE_ID: u8 = 0x48;
 
 #[derive(Clone, Copy)]
 enum RegCntl1 {
     PowerDn = 0,
+    SingleMeas = 0x01,
     ContMeas1 = 0x02,  // 8hz sampling
     ContMeas2 = 0x06,  // 100hz sampling
+    SelfTest = 0x08,
     FuseRom = 0x0f,
     Sensitivity16bit = 1 << 4,
 }
@@ -49,12 +48,62 @@ impl RegCntl1 {
     }
 }
 
+#[derive(Clone, Copy)]
+enum RegCntl2 {
+    SoftReset = 0x01,
+}
+
+impl RegCntl2 {
+    fn mask(&self) -> u8 {
+        *self as u8
+    }
+}
+
+/// ASTC SELF bit: generates the internal self-test magnetic field.
+const ASTC_SELF: u8 = 0x40;
+
+/// Datasheet self-test pass window, in adjusted LSB, for the 16-bit range
+/// used while `self_test` runs.
+const SELF_TEST_XY_RANGE: (f32, f32) = (-200.0, 200.0);
+const SELF_TEST_Z_RANGE: (f32, f32) = (-3200.0, -800.0);
+
+/// Which axis failed a self-test.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+/// Errors that may occur while running `self_test`.
+#[derive(Debug)]
+pub enum SelfTestError<E> {
+    /// Self-test is only implemented for `Chip::Ak8963` so far.
+    UnsupportedChip(Chip),
+    /// DRDY never asserted within the self-test timeout.
+    Timeout,
+    /// The given axis fell outside the datasheet's self-test pass window.
+    OutOfRange(Axis),
+    /// An i2c issue occurred.
+    I2c(E),
+}
+
+impl<E> From<E> for SelfTestError<E> {
+    fn from(e: E) -> Self {
+        SelfTestError::I2c(e)
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum SampleRate {
     /// Continuous measurement mode 1
     Opt8Hz,
     /// Continuous measurement mode 2
     Opt100Hz,
+    /// Single-measurement mode: the sensor stays powered down and takes no
+    /// samples on its own. Call `trigger_single_measurement` to start a
+    /// conversion, then `try_read_sample` to poll for the result.
+    Single,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -66,8 +115,8 @@ pub enum Sensitivity {
 }
 
 impl Sensitivity {
-    fn scalar(&self) -> f32 {
-        MEAS_RANGE / match *self {
+    fn scalar(&self, chip: Chip) -> f32 {
+        chip.meas_range() / match *self {
             Sensitivity::Opt14bit => 8192.0,
             Sensitivity::Opt16bit => 32768.0,
         }
@@ -87,75 +136,255 @@ pub struct Ak8963Sample {
 
 /// Errors that may occur when reading a sample.
 #[derive(Debug)]
-pub enum ReadSampleError {
+pub enum ReadSampleError<E> {
+    /// No new data is ready.
+    DataNotReady,
+    /// `try_read_sample` was called in `SampleRate::Single` mode without a
+    /// prior `trigger_single_measurement`.
+    NoMeasurementTriggered,
+    /// An i2c issue occurred.
+    I2c(E),
+}
+
+impl<E> From<E> for ReadSampleError<E> {
+    fn from(e: E) -> Self {
+        ReadSampleError::I2c(e)
+    }
+}
+
+/// Errors that may occur while constructing or (re-)configuring an `Ak8963`.
+#[derive(Debug)]
+pub enum ConfigError<E> {
+    /// The WIA register didn't return the expected AK8963 device id. Likely
+    /// means the wrong bus or address was used, or a different chip is
+    /// attached.
+    WrongDeviceId(u8),
+    /// An i2c issue occurred.
+    I2c(E),
+}
+
+/// Errors that may occur while running `collect_calibration`.
+#[derive(Debug)]
+pub enum CalibrationError<E> {
+    /// The sensor wasn't rotated far enough through the given axis during
+    /// the run to fit a soft-iron scale for it.
+    InsufficientRotation(Axis),
     /// No new data is ready.
     DataNotReady,
     /// An i2c issue occurred.
-    I2c(LinuxI2CError),
+    I2c(E),
+}
+
+impl<E> From<ReadSampleError<E>> for CalibrationError<E> {
+    fn from(e: ReadSampleError<E>) -> Self {
+        match e {
+            ReadSampleError::DataNotReady => CalibrationError::DataNotReady,
+            ReadSampleError::NoMeasurementTriggered => CalibrationError::DataNotReady,
+            ReadSampleError::I2c(e) => CalibrationError::I2c(e),
+        }
+    }
+}
+
+impl<E> From<E> for ConfigError<E> {
+    fn from(e: E) -> Self {
+        ConfigError::I2c(e)
+    }
 }
 
 /// Magnetometer.
-pub struct Ak8963 {
-    i2c_dev: LinuxI2CDevice,
+pub struct Ak8963<I2C> {
+    i2c_dev: I2C,
+    address: u8,
+    pub chip: Chip,
     pub factory_adjust: Array1<f32>,
     pub sensitivity: Sensitivity,
+    pub sample_rate: SampleRate,
+    /// Set by `trigger_single_measurement`, cleared once `try_read_sample`
+    /// delivers a result. Only meaningful in `SampleRate::Single` mode.
+    single_measurement_pending: bool,
 }
 
-impl Ak8963 {
-
-    /// Sets up and configures the AK8963.
-    /// If i2c_addr isn't specified, defaults to 0x0c.
-    pub fn new(
-            i2c_bus: i32, i2c_addr: Option<u16>, sensitivity: Sensitivity,
-            sample_rate: SampleRate)
-            -> Result<Ak8963, LinuxI2CError> {
-        let mut i2c_dev = LinuxI2CDevice::new(
-            get_i2c_bus_path(i2c_bus), i2c_addr.unwrap_or(0x0c))?;
-
-        let factory_adjust = Ak8963::read_sensitivity_adjustment(&mut i2c_dev)?;
+impl<I2C, E> Ak8963<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+{
+    /// Sets up and configures the magnetometer.
+    /// If i2c_addr isn't specified, defaults to 0x0c. If chip isn't
+    /// specified, defaults to `Chip::Ak8963`.
+    pub fn new<D: DelayMs<u8>>(
+            mut i2c_dev: I2C, i2c_addr: Option<u8>, chip: Option<Chip>,
+            sensitivity: Sensitivity, sample_rate: SampleRate, delay: &mut D)
+            -> Result<Ak8963<I2C>, ConfigError<E>> {
+        let address = i2c_addr.unwrap_or(0x0c);
+        let chip = chip.unwrap_or_default();
+
+        let factory_adjust = Ak8963::read_sensitivity_adjustment(
+            &mut i2c_dev, address, chip, delay)?;
 
         let mut ak = Ak8963 {
             i2c_dev,
+            address,
+            chip,
             factory_adjust,
             sensitivity,
+            sample_rate,
+            single_measurement_pending: false,
         };
 
-        ak.initialize(sensitivity, sample_rate)?;
+        ak.initialize(sensitivity, sample_rate, delay)?;
 
         Ok(ak)
     }
 
+    /// Performs a soft reset via CNTL2, which returns all registers to their
+    /// defaults, then re-reads the Fuse-ROM sensitivity adjustment and
+    /// re-applies the previously configured sensitivity/sample rate.
+    pub fn reset<D: DelayMs<u8>>(&mut self, delay: &mut D) -> Result<(), ConfigError<E>> {
+        self.i2c_dev.write(self.address, &[self.chip.cntl2_addr(), RegCntl2::SoftReset.mask()])?;
+        delay.delay_ms(1);
+
+        self.factory_adjust = Ak8963::read_sensitivity_adjustment(
+            &mut self.i2c_dev, self.address, self.chip, delay)?;
+
+        self.initialize(self.sensitivity, self.sample_rate, delay)?;
+        self.single_measurement_pending = false;
+
+        Ok(())
+    }
+
+    /// Reconfigures the sensitivity/sample rate without rebuilding the
+    /// struct.
+    pub fn set_mode<D: DelayMs<u8>>(
+            &mut self, sensitivity: Sensitivity, sample_rate: SampleRate,
+            delay: &mut D)
+            -> Result<(), E> {
+        self.initialize(sensitivity, sample_rate, delay)?;
+        self.sensitivity = sensitivity;
+        self.sample_rate = sample_rate;
+        self.single_measurement_pending = false;
+        Ok(())
+    }
+
+    /// Exercises the on-chip self-test magnetic field to validate sensor
+    /// health, the way the AKM IIO drivers do. Leaves the sensor back in its
+    /// previously configured operating mode when done.
+    pub fn self_test<D: DelayMs<u8>>(
+            &mut self, delay: &mut D) -> Result<Ak8963Sample, SelfTestError<E>> {
+        const SELF_TEST_RETRIES: u8 = 20;
+
+        if self.chip != Chip::Ak8963 {
+            return Err(SelfTestError::UnsupportedChip(self.chip));
+        }
+
+        // self_test reconfigures CNTL1/ASTC directly, invalidating any
+        // single-measurement conversion that may have been in flight.
+        self.single_measurement_pending = false;
+
+        // Power down
+        self.i2c_dev.write(self.address, &[self.chip.cntl1_addr(), RegCntl1::PowerDn.mask()])?;
+        delay.delay_ms(1);
+
+        // Generate the internal self-test magnetic field
+        self.i2c_dev.write(self.address, &[self.chip.astc_addr(), ASTC_SELF])?;
+        delay.delay_ms(1);
+
+        // Enter self-test mode at 16-bit resolution
+        self.i2c_dev.write(self.address, &[
+            self.chip.cntl1_addr(),
+            RegCntl1::SelfTest.mask() | RegCntl1::Sensitivity16bit.mask(),
+        ])?;
+
+        let mut st1: [u8; 1] = [0u8; 1];
+        let mut ready = false;
+        for _ in 0..SELF_TEST_RETRIES {
+            self.i2c_dev.write_read(self.address, &[self.chip.st1_addr()], &mut st1)?;
+            if (st1[0] & 1) != 0 {
+                ready = true;
+                break;
+            }
+            delay.delay_ms(1);
+        }
+
+        let mut buf: [u8; 7] = [0u8; 7];
+        if ready {
+            self.i2c_dev.write_read(self.address, &[self.chip.hxl_addr()], &mut buf)?;
+        }
+
+        // Clear ASTC and power down again
+        self.i2c_dev.write(self.address, &[self.chip.astc_addr(), 0x00])?;
+        delay.delay_ms(1);
+        self.i2c_dev.write(self.address, &[self.chip.cntl1_addr(), RegCntl1::PowerDn.mask()])?;
+        delay.delay_ms(1);
+
+        // Restore the sensor's previous operating mode.
+        self.initialize(self.sensitivity, self.sample_rate, delay)?;
+
+        if !ready {
+            return Err(SelfTestError::Timeout);
+        }
+
+        let mag_raw = array![
+            LittleEndian::read_i16(&buf[0 .. 2]),
+            LittleEndian::read_i16(&buf[2 .. 4]),
+            LittleEndian::read_i16(&buf[4 .. 6]),
+        ];
+        let adjusted = mag_raw.map(|e| *e as f32) * self.factory_adjust.clone();
+
+        if adjusted[0] < SELF_TEST_XY_RANGE.0 || adjusted[0] > SELF_TEST_XY_RANGE.1 {
+            return Err(SelfTestError::OutOfRange(Axis::X));
+        }
+        if adjusted[1] < SELF_TEST_XY_RANGE.0 || adjusted[1] > SELF_TEST_XY_RANGE.1 {
+            return Err(SelfTestError::OutOfRange(Axis::Y));
+        }
+        if adjusted[2] < SELF_TEST_Z_RANGE.0 || adjusted[2] > SELF_TEST_Z_RANGE.1 {
+            return Err(SelfTestError::OutOfRange(Axis::Z));
+        }
+
+        Ok(Ak8963Sample {
+            mag: Sensitivity::Opt16bit.scalar(self.chip) * &self.factory_adjust *
+                mag_raw.map(|e| *e as f32),
+            mag_raw,
+            data_overrun: false,
+        })
+    }
+
     /// Reads factory set sensitivity adjustment values from Fuse ROM.
-    pub fn read_sensitivity_adjustment(i2c_dev: &mut LinuxI2CDevice) -> Result<Array1<f32>, LinuxI2CError> {
+    /// Verifies the WIA "who am I" register first, so a wrong bus/address or
+    /// a different chip is reported rather than producing garbage samples.
+    pub fn read_sensitivity_adjustment<D: DelayMs<u8>>(
+            i2c_dev: &mut I2C, address: u8, chip: Chip, delay: &mut D)
+            -> Result<Array1<f32>, ConfigError<E>> {
+        let mut wia: [u8; 1] = [0u8; 1];
+        i2c_dev.write_read(address, &[chip.wia_addr()], &mut wia)?;
+        if wia[0] != DEVICE_ID {
+            return Err(ConfigError::WrongDeviceId(wia[0]));
+        }
+
         // Power down mag
-        i2c_dev.write(&[Ak8963Reg::Cntl1.addr(), RegCntl1::PowerDn.mask()])?;
-        thread::sleep(time::Duration::from_millis(1));
+        i2c_dev.write(address, &[chip.cntl1_addr(), RegCntl1::PowerDn.mask()])?;
+        delay.delay_ms(1);
 
         // Enter FUSE ROM mode
-        i2c_dev.write(&[Ak8963Reg::Cntl1.addr(), RegCntl1::FuseRom.mask()])?;
-        thread::sleep(time::Duration::from_millis(1));
+        i2c_dev.write(address, &[chip.cntl1_addr(), RegCntl1::FuseRom.mask()])?;
+        delay.delay_ms(1);
 
         // Read sensitivity values from ROM
         let mut buf: [u8; 3] = [0u8; 3];
-        i2c_dev.write(&[Ak8963Reg::Asax.addr()])?;
-        i2c_dev.read(&mut buf)?;
+        i2c_dev.write_read(address, &[chip.asa_addr()], &mut buf)?;
 
-        let factory_adjust = array![
-            ((buf[0] - 128) as f32)/256.0 + 1.0,
-            ((buf[1] - 128) as f32)/256.0 + 1.0,
-            ((buf[2] - 128) as f32)/256.0 + 1.0,
-        ];
+        let factory_adjust = chip.fuse_rom_adjustment(buf);
 
         // Power down mag again
-        i2c_dev.write(&[Ak8963Reg::Cntl1.addr(), RegCntl1::PowerDn.mask()])?;
-        thread::sleep(time::Duration::from_micros(100));
+        i2c_dev.write(address, &[chip.cntl1_addr(), RegCntl1::PowerDn.mask()])?;
+        delay.delay_ms(1);
 
         Ok(factory_adjust)
     }
 
-    fn initialize(
-            &mut self, sensitivity: Sensitivity, sample_rate: SampleRate)
-            -> Result<(), LinuxI2CError> {
+    fn initialize<D: DelayMs<u8>>(
+            &mut self, sensitivity: Sensitivity, sample_rate: SampleRate,
+            delay: &mut D)
+            -> Result<(), E> {
 
         let mut cntl1_byte = 0u8;
         match sensitivity {
@@ -171,21 +400,36 @@ impl Ak8963 {
             SampleRate::Opt100Hz => {
                 cntl1_byte |= RegCntl1::ContMeas2.mask()
             },
+            SampleRate::Single => {
+                // Power down; trigger_single_measurement writes CNTL1 on
+                // demand for each conversion.
+                cntl1_byte |= RegCntl1::PowerDn.mask()
+            },
         }
-        self.i2c_dev.write(&[Ak8963Reg::Cntl1.addr(), cntl1_byte])?;
+        self.i2c_dev.write(self.address, &[self.chip.cntl1_addr(), cntl1_byte])?;
 
-        thread::sleep(time::Duration::from_micros(100));
+        delay.delay_ms(1);
 
         return Ok(())
     }
 
+    /// Starts a single conversion in `SampleRate::Single` mode. The sensor
+    /// powers itself back down once the conversion completes; poll
+    /// `try_read_sample` for the result.
+    pub fn trigger_single_measurement(&mut self) -> Result<(), E> {
+        let mut cntl1_byte = RegCntl1::SingleMeas.mask();
+        if let Sensitivity::Opt16bit = self.sensitivity {
+            cntl1_byte |= RegCntl1::Sensitivity16bit.mask();
+        }
+        self.i2c_dev.write(self.address, &[self.chip.cntl1_addr(), cntl1_byte])?;
+        self.single_measurement_pending = true;
+        Ok(())
+    }
+
     /// Returns None if magnetometer reports magnetic field saturation.
-    pub fn read_sample(&mut self) -> Result<Option<Ak8963Sample>, ReadSampleError> {
+    pub fn read_sample(&mut self) -> Result<Option<Ak8963Sample>, ReadSampleError<E>> {
         let mut buf1: [u8; 1] = [0u8; 1];
-        self.i2c_dev.write(&[Ak8963Reg::St1.addr()])
-            .map_err(|e| ReadSampleError::I2c(e))?;
-        self.i2c_dev.read(&mut buf1)
-            .map_err(|e| ReadSampleError::I2c(e))?;
+        self.i2c_dev.write_read(self.address, &[self.chip.st1_addr()], &mut buf1)?;
 
         // Check DRDY (data ready) bit
         if (buf1[0] & 1) == 0 {
@@ -193,13 +437,11 @@ impl Ak8963 {
         }
 
         let mut buf: [u8; 7] = [0u8; 7];
-        self.i2c_dev.write(&[Ak8963Reg::Hxl.addr()])
-            .map_err(|e| ReadSampleError::I2c(e))?;
-        self.i2c_dev.read(&mut buf)
-            .map_err(|e| ReadSampleError::I2c(e))?;
+        self.i2c_dev.write_read(self.address, &[self.chip.hxl_addr()], &mut buf)?;
 
-        let mut sample = Ak8963::parse_sample_helper(
+        let mut sample = Ak8963::<I2C>::parse_sample_helper(
             &buf,
+            self.chip,
             self.sensitivity,
             &self.factory_adjust);
 
@@ -213,10 +455,79 @@ impl Ak8963 {
         Ok(sample)
     }
 
+    /// Non-blocking read for single-measurement/externally-triggered use.
+    /// In `SampleRate::Single` mode, fails with
+    /// `ReadSampleError::NoMeasurementTriggered` unless
+    /// `trigger_single_measurement` was called first, and otherwise returns
+    /// `Err(ReadSampleError::DataNotReady)` immediately (without blocking)
+    /// while the conversion is still in flight, leaving it to the caller to
+    /// poll instead of driving the sensor's free-running continuous output.
+    pub fn try_read_sample(&mut self) -> Result<Option<Ak8963Sample>, ReadSampleError<E>> {
+        if let SampleRate::Single = self.sample_rate {
+            if !self.single_measurement_pending {
+                return Err(ReadSampleError::NoMeasurementTriggered);
+            }
+        }
+
+        let result = self.read_sample();
+        if !matches!(result, Err(ReadSampleError::DataNotReady)) {
+            self.single_measurement_pending = false;
+        }
+        result
+    }
+
+    /// Like `read_sample`, but applies a hard-iron/soft-iron `Calibration`
+    /// to the result.
+    pub fn read_sample_calibrated(
+            &mut self, calibration: &Calibration)
+            -> Result<Option<Ak8963Sample>, ReadSampleError<E>> {
+        let sample = self.read_sample()?;
+        Ok(sample.map(|mut sample| {
+            sample.mag = calibration.apply(&sample.mag);
+            sample
+        }))
+    }
+
+    /// Spins the sensor through `samples` readings (the caller should be
+    /// rotating it through many orientations while this runs) and fits a
+    /// diagonal hard-iron/soft-iron `Calibration` from the per-axis min/max
+    /// observed: hard-iron offset is `(max+min)/2`, soft-iron scale is the
+    /// ratio of each axis's half-range to the average half-range.
+    ///
+    /// Fails with `CalibrationError::InsufficientRotation` if the sensor
+    /// wasn't rotated far enough through one of the axes during the run.
+    pub fn collect_calibration<D: DelayMs<u8>>(
+            &mut self, samples: usize, delay: &mut D)
+            -> Result<Calibration, CalibrationError<E>> {
+        let mut min = [core::f32::INFINITY; 3];
+        let mut max = [core::f32::NEG_INFINITY; 3];
+
+        let mut collected = 0;
+        while collected < samples {
+            match self.read_sample() {
+                Ok(Some(sample)) => {
+                    for axis in 0 .. 3 {
+                        let v = sample.mag[axis];
+                        if v < min[axis] { min[axis] = v; }
+                        if v > max[axis] { max[axis] = v; }
+                    }
+                    collected += 1;
+                },
+                Ok(None) => {},
+                Err(ReadSampleError::DataNotReady) => {},
+                Err(e) => return Err(e.into()),
+            }
+            delay.delay_ms(1);
+        }
+
+        calibration::fit_diagonal(min, max)
+            .map_err(CalibrationError::InsufficientRotation)
+    }
+
     fn parse_sample_helper(
-            data: &[u8], sensitivity: Sensitivity,
+            data: &[u8], chip: Chip, sensitivity: Sensitivity,
             factory_adjust: &Array1<f32>) -> Option<Ak8963Sample> {
-        if (data[6] & (1 << 3)) > 0 {
+        if (data[6] & chip.saturation_bit()) > 0 {
             // Magnet saturation
             return None;
         }
@@ -227,7 +538,7 @@ impl Ak8963 {
             LittleEndian::read_i16(&data[4 .. 6]),
         ];
 
-        let mag = sensitivity.scalar() * factory_adjust *
+        let mag = sensitivity.scalar(chip) * factory_adjust *
             mag_raw.map(|e| *e as f32);
 
         Some(Ak8963Sample {
@@ -239,40 +550,162 @@ impl Ak8963 {
 
     /// Returns None if magnetometer reports magnetic field saturation.
     pub fn parse_sample_data(&mut self, data: &[u8]) -> Option<Ak8963Sample> {
-        Ak8963::parse_sample_helper(data, self.sensitivity, &self.factory_adjust)
+        Ak8963::<I2C>::parse_sample_helper(data, self.chip, self.sensitivity, &self.factory_adjust)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Ak8963, SampleRate, Sensitivity};
-    use std::env;
-
-    fn get_i2c_bus() -> i32 {
-        match env::var("AK8963_I2C_BUS") {
-            Ok(bus_string) => {
-                bus_string.parse().expect(
-                    "Could not convert AK8963_I2C_BUS env var to i32.")
-            },
-            Err(_) => 1,
+    use super::*;
+    use std::collections::HashMap;
+
+    // Hardware-only: exercises a real sensor over the `linux` feature's
+    // i2cdev constructor, so it can't run as part of a normal `cargo test`.
+    #[cfg(feature = "linux")]
+    mod hardware {
+        use super::super::{Ak8963, SampleRate, Sensitivity};
+        use std::env;
+
+        fn get_i2c_bus() -> i32 {
+            match env::var("AK8963_I2C_BUS") {
+                Ok(bus_string) => {
+                    bus_string.parse().expect(
+                        "Could not convert AK8963_I2C_BUS env var to i32.")
+                },
+                Err(_) => 1,
+            }
+        }
+
+        fn get_i2c_addr() -> Option<u8> {
+            match env::var("AK8963_I2C_ADDR") {
+                Ok(addr_string) => {
+                    Some(addr_string.parse().expect(
+                        "Could not convert AK8963_I2C_ADDR env var to u8."))
+                },
+                Err(_) => None,
+            }
+        }
+
+        #[test]
+        fn basic() {
+            let mut ak8963 = Ak8963::new_linux(
+                get_i2c_bus(), get_i2c_addr(), None, Sensitivity::Opt16bit,
+                SampleRate::Opt100Hz).unwrap();
+            ak8963.read_sample().unwrap();
         }
     }
 
-    fn get_i2c_addr() -> Option<u16> {
-        match env::var("AK8963_I2C_ADDR") {
-            Ok(addr_string) => {
-                Some(addr_string.parse().expect(
-                    "Could not convert AK8963_I2C_ADDR env var to u16."))
-            },
-            Err(_) => None,
+    /// A fake i2c bus: `write_read` answers each register address with a
+    /// canned response installed via `respond`, and every `write` is
+    /// recorded for inspection. Good enough to drive the WIA/ASA/ST1/HXL
+    /// register protocol this driver speaks without real hardware.
+    #[derive(Default)]
+    struct MockI2c {
+        responses: HashMap<u8, Vec<u8>>,
+        writes: Vec<(u8, u8)>,
+    }
+
+    impl MockI2c {
+        fn respond(mut self, reg: u8, bytes: &[u8]) -> Self {
+            self.responses.insert(reg, bytes.to_vec());
+            self
         }
     }
 
+    impl Write for MockI2c {
+        type Error = ();
+
+        fn write(&mut self, _address: u8, bytes: &[u8]) -> Result<(), ()> {
+            self.writes.push((bytes[0], bytes[1]));
+            Ok(())
+        }
+    }
+
+    impl WriteRead for MockI2c {
+        type Error = ();
+
+        fn write_read(
+                &mut self, _address: u8, bytes: &[u8], buffer: &mut [u8])
+                -> Result<(), ()> {
+            let response = &self.responses[&bytes[0]];
+            buffer.copy_from_slice(response);
+            Ok(())
+        }
+    }
+
+    struct NoopDelay;
+
+    impl DelayMs<u8> for NoopDelay {
+        fn delay_ms(&mut self, _ms: u8) {}
+    }
+
+    #[test]
+    fn new_rejects_wrong_device_id() {
+        let bus = MockI2c::default()
+            .respond(Chip::Ak8963.wia_addr(), &[0x00]);
+        let result = Ak8963::new(
+            bus, None, None, Sensitivity::Opt16bit, SampleRate::Opt100Hz,
+            &mut NoopDelay);
+        assert!(matches!(
+            result, Err(ConfigError::WrongDeviceId(0x00))));
+    }
+
+    /// A bus that answers WIA/ASA/ST1 for a default-configured AK8963, ready
+    /// to be wrapped in `Ak8963::new`.
+    fn healthy_ak8963_bus() -> MockI2c {
+        MockI2c::default()
+            .respond(Chip::Ak8963.wia_addr(), &[DEVICE_ID])
+            .respond(Chip::Ak8963.asa_addr(), &[128, 128, 128])
+            .respond(Chip::Ak8963.st1_addr(), &[0x01])
+    }
+
+    fn hxl_response(raw: [i16; 3], status: u8) -> [u8; 7] {
+        let mut buf = [0u8; 7];
+        LittleEndian::write_i16(&mut buf[0 .. 2], raw[0]);
+        LittleEndian::write_i16(&mut buf[2 .. 4], raw[1]);
+        LittleEndian::write_i16(&mut buf[4 .. 6], raw[2]);
+        buf[6] = status;
+        buf
+    }
+
     #[test]
-    fn basic() {
+    fn self_test_passes_within_datasheet_range() {
+        let bus = healthy_ak8963_bus()
+            .respond(Chip::Ak8963.hxl_addr(), &hxl_response([50, -50, -1000], 0));
         let mut ak8963 = Ak8963::new(
-            get_i2c_bus(), get_i2c_addr(), Sensitivity::Opt16bit,
-            SampleRate::Opt100Hz).unwrap();
-        ak8963.read_sample().unwrap();
+            bus, None, None, Sensitivity::Opt16bit, SampleRate::Opt100Hz,
+            &mut NoopDelay).unwrap();
+
+        let sample = ak8963.self_test(&mut NoopDelay).unwrap();
+        assert_eq!(sample.mag_raw, array![50, -50, -1000]);
+    }
+
+    #[test]
+    fn self_test_reports_out_of_range_axis() {
+        let bus = healthy_ak8963_bus()
+            .respond(Chip::Ak8963.hxl_addr(), &hxl_response([50, -50, -100], 0));
+        let mut ak8963 = Ak8963::new(
+            bus, None, None, Sensitivity::Opt16bit, SampleRate::Opt100Hz,
+            &mut NoopDelay).unwrap();
+
+        let result = ak8963.self_test(&mut NoopDelay);
+        assert!(matches!(
+            result, Err(SelfTestError::OutOfRange(Axis::Z))));
+    }
+
+    #[test]
+    fn self_test_clears_pending_single_measurement() {
+        let bus = healthy_ak8963_bus()
+            .respond(Chip::Ak8963.hxl_addr(), &hxl_response([50, -50, -1000], 0));
+        let mut ak8963 = Ak8963::new(
+            bus, None, None, Sensitivity::Opt16bit, SampleRate::Single,
+            &mut NoopDelay).unwrap();
+
+        ak8963.trigger_single_measurement().unwrap();
+        ak8963.self_test(&mut NoopDelay).unwrap();
+
+        assert!(matches!(
+            ak8963.try_read_sample(),
+            Err(ReadSampleError::NoMeasurementTriggered)));
     }
 }
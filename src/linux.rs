@@ -0,0 +1,39 @@
+//! Convenience constructor for Linux i2cdev hosts, built on top of the
+//! generic `embedded-hal` driver.
+
+extern crate linux_embedded_hal;
+
+use self::linux_embedded_hal::{Delay, I2cdev};
+
+use super::{Ak8963, Chip, ConfigError, SampleRate, Sensitivity};
+
+/// Error produced by the Linux convenience constructor.
+#[derive(Debug)]
+pub enum LinuxError {
+    /// Failed to open or configure the `/dev/i2c-*` device node.
+    Io(std::io::Error),
+    /// An error occurred configuring or talking to the magnetometer.
+    Ak8963(ConfigError<linux_embedded_hal::I2CError>),
+}
+
+impl From<ConfigError<linux_embedded_hal::I2CError>> for LinuxError {
+    fn from(e: ConfigError<linux_embedded_hal::I2CError>) -> Self {
+        LinuxError::Ak8963(e)
+    }
+}
+
+impl Ak8963<I2cdev> {
+    /// Sets up and configures the magnetometer on a Linux i2cdev bus.
+    /// If i2c_addr isn't specified, defaults to 0x0c. If chip isn't
+    /// specified, defaults to `Chip::Ak8963`.
+    pub fn new_linux(
+            i2c_bus: i32, i2c_addr: Option<u8>, chip: Option<Chip>,
+            sensitivity: Sensitivity, sample_rate: SampleRate)
+            -> Result<Ak8963<I2cdev>, LinuxError> {
+        let i2c_dev = I2cdev::new(format!("/dev/i2c-{}", i2c_bus))
+            .map_err(LinuxError::Io)?;
+
+        Ak8963::new(i2c_dev, i2c_addr, chip, sensitivity, sample_rate, &mut Delay)
+            .map_err(LinuxError::from)
+    }
+}